@@ -0,0 +1,140 @@
+use axum::{
+    extract::{FromRef, FromRequest, Request},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+
+use crate::{StripeEvent, StripeListener};
+
+/// Rejection returned when the `StripeEvent` extractor can't verify or parse a webhook.
+/// Verification failures (missing or malformed `Stripe-Signature` header, bad signature,
+/// stale timestamp) map to `401`; an unreadable or non-UTF-8 body and bad JSON map to `400`.
+pub struct StripeWebhookRejection {
+    status: StatusCode,
+    message: String,
+}
+
+impl IntoResponse for StripeWebhookRejection {
+    fn into_response(self) -> Response {
+        (self.status, self.message).into_response()
+    }
+}
+
+impl<S> FromRequest<S> for StripeEvent
+where
+    StripeListener: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = StripeWebhookRejection;
+
+    /// Reads the raw request body, verifies it against the `Stripe-Signature` header
+    /// using the `StripeListener` in Axum state, and returns the parsed event.
+    ///
+    /// Signature verification needs the exact bytes Stripe signed, so this extractor
+    /// must consume the body itself rather than relying on `Json<T>` to pre-parse it.
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let listener = StripeListener::from_ref(state);
+        let headers = req.headers().clone();
+
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| StripeWebhookRejection {
+                status: StatusCode::BAD_REQUEST,
+                message: format!("failed to read request body: {e}"),
+            })?;
+
+        let payload = std::str::from_utf8(&body).map_err(|e| StripeWebhookRejection {
+            status: StatusCode::BAD_REQUEST,
+            message: format!("request body is not valid utf-8: {e}"),
+        })?;
+
+        // Verify once, then parse the already-verified payload, instead of letting
+        // `process` re-run the HMAC check just so we can bucket the error by status code.
+        listener
+            .verify(&headers, payload)
+            .map_err(|e| StripeWebhookRejection {
+                status: StatusCode::UNAUTHORIZED,
+                message: e.to_string(),
+            })?;
+
+        listener
+            .parse_event(payload)
+            .map_err(|e| StripeWebhookRejection {
+                status: StatusCode::BAD_REQUEST,
+                message: e.to_string(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, routing::post};
+    use http::Request as HttpRequest;
+    use tower::ServiceExt;
+
+    const TIMESTAMP: i64 = 1_000_000;
+    const SECRET: &str = "whsec_test";
+
+    fn app(listener: StripeListener) -> Router {
+        Router::new()
+            .route("/webhook", post(|_event: StripeEvent| async { StatusCode::OK }))
+            .with_state(listener)
+    }
+
+    #[tokio::test]
+    async fn extracts_a_verified_event() {
+        let payload = r#"{"id":"evt_1","type":"unknown.event","data":{"object":{}}}"#;
+        let signature = StripeListener::sign(SECRET, payload, TIMESTAMP);
+        let listener = StripeListener::new(SECRET.to_string()).with_clock(|| TIMESTAMP as u64);
+
+        let response = app(listener)
+            .oneshot(
+                HttpRequest::post("/webhook")
+                    .header("Stripe-Signature", signature)
+                    .body(Body::from(payload))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_signature_with_401() {
+        let listener = StripeListener::new(SECRET.to_string()).with_clock(|| TIMESTAMP as u64);
+
+        let response = app(listener)
+            .oneshot(
+                HttpRequest::post("/webhook")
+                    .header("Stripe-Signature", format!("t={TIMESTAMP},v1=deadbeef"))
+                    .body(Body::from("{}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_body_with_400() {
+        let payload = "not json";
+        let signature = StripeListener::sign(SECRET, payload, TIMESTAMP);
+        let listener = StripeListener::new(SECRET.to_string()).with_clock(|| TIMESTAMP as u64);
+
+        let response = app(listener)
+            .oneshot(
+                HttpRequest::post("/webhook")
+                    .header("Stripe-Signature", signature)
+                    .body(Body::from(payload))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
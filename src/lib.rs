@@ -7,16 +7,39 @@ use http::HeaderMap;
 use serde::Deserialize;
 use serde_json::Value;
 use sha2::Sha256;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use subtle::ConstantTimeEq;
 
+#[cfg(feature = "axum")]
+mod extract;
+#[cfg(feature = "axum")]
+pub use extract::StripeWebhookRejection;
+
 type HmacSha256 = Hmac<Sha256>;
 
+/// Default replay-protection window, matching Stripe's own `construct_event` helpers.
+const DEFAULT_TOLERANCE: Duration = Duration::from_secs(300);
+
 pub enum StripeEvent {
-    CheckoutSessionCompleted(Value),
-    CustomerSubscriptionDeleted(Value),
+    CheckoutSessionCompleted(CheckoutSession, Value),
+    CustomerSubscriptionDeleted(Subscription, Value),
     Unknown(Value),
 }
 
+impl StripeEvent {
+    /// The untouched `data.object` payload Stripe sent, for fields this crate
+    /// doesn't type yet or forward-compatibility with new API versions.
+    pub fn raw(&self) -> &Value {
+        match self {
+            StripeEvent::CheckoutSessionCompleted(_, raw) => raw,
+            StripeEvent::CustomerSubscriptionDeleted(_, raw) => raw,
+            StripeEvent::Unknown(raw) => raw,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct StripeEventRequest {
     pub id: String,
@@ -29,88 +52,427 @@ pub struct StripeEventData {
     pub object: Value,
 }
 
+/// `checkout.session.completed`'s `data.object`.
+#[derive(Debug, Deserialize)]
+pub struct CheckoutSession {
+    pub id: String,
+    pub customer: Option<String>,
+    pub subscription: Option<String>,
+    pub payment_status: String,
+    pub amount_total: Option<i64>,
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub metadata: std::collections::HashMap<String, String>,
+}
+
+/// `customer.subscription.deleted`'s `data.object`.
+#[derive(Debug, Deserialize)]
+pub struct Subscription {
+    pub id: String,
+    pub customer: String,
+    pub status: String,
+    pub current_period_end: i64,
+    pub cancel_at_period_end: bool,
+    pub items: Value,
+}
+
+#[derive(Clone)]
 pub struct StripeListener {
-    secret: String,
+    secrets: Vec<String>,
+    tolerance: Option<Duration>,
+    now: Arc<dyn Fn() -> u64 + Send + Sync>,
 }
 impl StripeListener {
     pub fn new(secret: String) -> Self {
-        Self { secret }
+        Self::new_with_secrets(vec![secret])
+    }
+
+    /// Construct a listener that accepts a payload if it matches any of the given secrets.
+    pub fn new_with_secrets(secrets: Vec<String>) -> Self {
+        Self {
+            secrets,
+            tolerance: Some(DEFAULT_TOLERANCE),
+            now: Arc::new(unix_now),
+        }
+    }
+
+    /// Construct a listener with a custom replay-protection window.
+    /// Pass `None` to disable the timestamp check entirely (useful for tests
+    /// that replay fixtures with a stale `t=`).
+    pub fn new_with_tolerance(secret: String, tolerance: Option<Duration>) -> Self {
+        Self::new(secret).with_tolerance(tolerance)
+    }
+
+    /// Override the replay-protection window. `None` disables the check.
+    pub fn with_tolerance(mut self, tolerance: Option<Duration>) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Override the clock used for the replay-protection check, so tests can
+    /// pin `now` to whatever timestamp a fixture was signed with.
+    pub fn with_clock(mut self, now: impl Fn() -> u64 + Send + Sync + 'static) -> Self {
+        self.now = Arc::new(now);
+        self
     }
 
     /// Process a Stripe webhook payload, verifying its signature and parsing the event.
     /// Returns a `StripeEvent` enum variant on success, or an error if verification or parsing fails.
     pub fn process(&self, headers: &HeaderMap, payload: &str) -> Result<StripeEvent> {
-        if !self.verify(headers, payload).is_none_or(|x| x) {
-            return Err(anyhow!("signature verification failed"));
-        }
+        self.verify(headers, payload)?;
+        self.parse_event(payload)
+    }
 
+    /// Parse an already-verified payload into a `StripeEvent`. Split out from `process` so
+    /// callers that verify up front (e.g. the Axum extractor, to pick a rejection status)
+    /// don't have to pay for a second HMAC pass just to parse the body.
+    pub(crate) fn parse_event(&self, payload: &str) -> Result<StripeEvent> {
         let event: StripeEventRequest = serde_json::from_str(payload)
             .map_err(|e| anyhow!("failed to parse Stripe event: {e}"))?;
 
         match event.r#type.as_str() {
             "checkout.session.completed" => {
-                Ok(StripeEvent::CheckoutSessionCompleted(event.data.object))
+                let checkout_session = serde_json::from_value(event.data.object.clone())
+                    .map_err(|e| anyhow!("failed to parse checkout.session.completed: {e}"))?;
+                Ok(StripeEvent::CheckoutSessionCompleted(
+                    checkout_session,
+                    event.data.object,
+                ))
             }
             "customer.subscription.deleted" => {
-                Ok(StripeEvent::CustomerSubscriptionDeleted(event.data.object))
+                let subscription = serde_json::from_value(event.data.object.clone())
+                    .map_err(|e| anyhow!("failed to parse customer.subscription.deleted: {e}"))?;
+                Ok(StripeEvent::CustomerSubscriptionDeleted(
+                    subscription,
+                    event.data.object,
+                ))
             }
             _ => Ok(StripeEvent::Unknown(event.data.object)),
         }
     }
 
-    fn verify(&self, headers: &HeaderMap, payload: &str) -> Option<bool> {
-        let signature_header = headers.get("Stripe-Signature")?.to_str().ok()?;
-        let valid = self.verify_signature(signature_header, payload);
+    pub(crate) fn verify(&self, headers: &HeaderMap, payload: &str) -> Result<()> {
+        let signature_header = Self::signature_header(headers)?;
+        self.verify_signature(signature_header, payload)
+    }
 
-        Some(valid)
+    fn verify_signature(&self, signature_header: &str, payload: &str) -> Result<()> {
+        self.verify_body(signature_header, payload.as_bytes())
     }
 
-    fn verify_signature(&self, signature_header: &str, payload: &str) -> bool {
-        let (timestamp, signature_hex) = match self.parse_signature(signature_header) {
-            Some(x) => x,
-            None => return false,
-        };
-        let signed_payload = format!("{timestamp}.{payload}");
+    /// Verify a webhook whose body arrives as a stream of byte chunks rather than a
+    /// buffered `&str`, so large payloads never have to be held in memory twice just
+    /// to check a signature.
+    pub fn verify_reader(&self, headers: &HeaderMap, reader: impl Read) -> Result<()> {
+        let signature_header = Self::signature_header(headers)?;
+        self.verify_body(signature_header, reader)
+    }
 
-        // HMAC
-        let mut mac = match <HmacSha256 as KeyInit>::new_from_slice(self.secret.as_bytes()) {
-            Ok(m) => m,
-            Err(_) => return false,
-        };
+    fn signature_header(headers: &HeaderMap) -> Result<&str> {
+        headers
+            .get("Stripe-Signature")
+            .ok_or_else(|| anyhow!("missing Stripe-Signature header"))?
+            .to_str()
+            .map_err(|e| anyhow!("invalid Stripe-Signature header: {e}"))
+    }
 
-        Update::update(&mut mac, signed_payload.as_bytes());
-        let expected = mac.finalize().into_bytes();
+    /// Core HMAC check, fed either a fully-buffered payload or a chunked reader.
+    fn verify_body(&self, signature_header: &str, mut reader: impl Read) -> Result<()> {
+        let (timestamp, signatures) = self
+            .parse_signature(signature_header)
+            .ok_or_else(|| anyhow!("missing t or v1 in Stripe-Signature header"))?;
+
+        // decode every header-provided hex signature to bytes
+        let sig_bytes: Vec<Vec<u8>> = signatures
+            .iter()
+            .filter_map(|sig| hex::decode(sig).ok())
+            .collect();
+        if sig_bytes.is_empty() {
+            return Err(anyhow!(
+                "no valid hex-encoded v1 signatures in Stripe-Signature header"
+            ));
+        }
+
+        let mut macs: Vec<HmacSha256> = self
+            .secrets
+            .iter()
+            .filter_map(|secret| <HmacSha256 as KeyInit>::new_from_slice(secret.as_bytes()).ok())
+            .collect();
+        if macs.is_empty() {
+            return Err(anyhow!("no usable webhook secrets configured"));
+        }
+
+        let prefix = format!("{timestamp}.");
+        for mac in &mut macs {
+            Update::update(mac, prefix.as_bytes());
+        }
+
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = reader
+                .read(&mut buf)
+                .map_err(|e| anyhow!("failed to read payload: {e}"))?;
+            if n == 0 {
+                break;
+            }
+            for mac in &mut macs {
+                Update::update(mac, &buf[..n]);
+            }
+        }
+
+        // accept the payload if it matches any (secret, signature) pair, so a new
+        // signing secret can be rolled out before the old one is retired
+        let matched = macs.into_iter().any(|mac| {
+            let expected = mac.finalize().into_bytes();
+            sig_bytes.iter().any(|sig| {
+                expected.len() == sig.len() && expected.as_slice().ct_eq(sig).unwrap_u8() == 1
+            })
+        });
+
+        if !matched {
+            return Err(anyhow!("signature verification failed"));
+        }
+
+        self.check_tolerance(&timestamp)
+    }
 
-        // decode header-provided hex signature to bytes
-        let sig_bytes = match hex::decode(signature_hex) {
-            Ok(v) => v,
-            Err(_) => return false,
+    /// Reject payloads whose `t=` timestamp has drifted too far from now, blocking replay
+    /// of a captured-but-otherwise-valid webhook. A `None` tolerance disables the check.
+    fn check_tolerance(&self, timestamp: &str) -> Result<()> {
+        let Some(tolerance) = self.tolerance else {
+            return Ok(());
         };
 
-        if expected.len() != sig_bytes.len() {
-            return false;
+        let ts: i64 = timestamp
+            .parse()
+            .map_err(|_| anyhow!("invalid t= timestamp in Stripe-Signature header"))?;
+        let now = (self.now)() as i64;
+        let drift = (now - ts).abs();
+
+        if drift > tolerance.as_secs() as i64 {
+            return Err(anyhow!(
+                "Stripe-Signature timestamp is outside the allowed tolerance (drift={drift}s)"
+            ));
         }
 
-        // constant-time compare
-        expected.as_slice().ct_eq(&sig_bytes).unwrap_u8() == 1
+        Ok(())
+    }
+
+    /// Produce a `t=<timestamp>,v1=<hex>` header value for `payload` signed with `secret`.
+    pub fn sign(secret: &str, payload: &str, timestamp: i64) -> String {
+        let signed_payload = format!("{timestamp}.{payload}");
+
+        let mut mac = <HmacSha256 as KeyInit>::new_from_slice(secret.as_bytes())
+            .expect("HMAC can take a key of any size");
+        Update::update(&mut mac, signed_payload.as_bytes());
+        let signature_hex = hex::encode(mac.finalize().into_bytes());
+
+        format!("t={timestamp},v1={signature_hex}")
     }
 
-    fn parse_signature(&self, header: &str) -> Option<(String, String)> {
+    fn parse_signature(&self, header: &str) -> Option<(String, Vec<String>)> {
         let mut ts = None;
-        let mut sig = None;
+        let mut sigs = Vec::new();
 
         for part in header.split(',') {
             let mut kv = part.splitn(2, '=');
             match (kv.next(), kv.next()) {
                 (Some("t"), Some(v)) => ts = Some(v.to_string()),
-                // pick the first v1 we see
-                (Some("v1"), Some(v)) if sig.is_none() => sig = Some(v.to_string()),
+                // collect every v1 so a secret rollover carries one signature per active secret
+                (Some("v1"), Some(v)) => sigs.push(v.to_string()),
                 _ => {}
             }
         }
-        match (ts, sig) {
-            (Some(t), Some(s)) => Some((t, s)),
+        match ts {
+            Some(t) if !sigs.is_empty() => Some((t, sigs)),
             _ => None,
         }
     }
 }
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &str = r#"{"id":"evt_1","type":"unknown.event","data":{"object":{}}}"#;
+    const TIMESTAMP: i64 = 1_000_000;
+    const SECRET: &str = "whsec_test";
+
+    fn headers_with_signature(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Stripe-Signature", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn accepts_timestamp_within_tolerance() {
+        let header = StripeListener::sign(SECRET, PAYLOAD, TIMESTAMP);
+        let listener =
+            StripeListener::new(SECRET.to_string()).with_clock(|| (TIMESTAMP + 100) as u64);
+
+        assert!(
+            listener
+                .verify(&headers_with_signature(&header), PAYLOAD)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_timestamp_outside_tolerance() {
+        let header = StripeListener::sign(SECRET, PAYLOAD, TIMESTAMP);
+        let listener =
+            StripeListener::new(SECRET.to_string()).with_clock(|| (TIMESTAMP + 301) as u64);
+
+        let err = listener
+            .verify(&headers_with_signature(&header), PAYLOAD)
+            .unwrap_err();
+        assert!(err.to_string().contains("tolerance"));
+    }
+
+    #[test]
+    fn accepts_old_secret_while_new_secret_is_also_configured() {
+        let old_secret = "whsec_old";
+        let new_secret = "whsec_new";
+        let header = StripeListener::sign(old_secret, PAYLOAD, TIMESTAMP);
+        let listener =
+            StripeListener::new_with_secrets(vec![new_secret.to_string(), old_secret.to_string()])
+                .with_clock(|| TIMESTAMP as u64);
+
+        assert!(
+            listener
+                .verify(&headers_with_signature(&header), PAYLOAD)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn sign_then_process_round_trips() {
+        let payload = r#"{"id":"evt_1","type":"checkout.session.completed","data":{"object":{"id":"cs_1","payment_status":"paid"}}}"#;
+        let header = StripeListener::sign(SECRET, payload, TIMESTAMP);
+        let listener = StripeListener::new(SECRET.to_string()).with_clock(|| TIMESTAMP as u64);
+
+        let event = listener
+            .process(&headers_with_signature(&header), payload)
+            .unwrap();
+        assert!(matches!(event, StripeEvent::CheckoutSessionCompleted(_, _)));
+    }
+
+    #[test]
+    fn verify_reader_agrees_with_buffered_verify() {
+        let header = StripeListener::sign(SECRET, PAYLOAD, TIMESTAMP);
+        let listener = StripeListener::new(SECRET.to_string()).with_clock(|| TIMESTAMP as u64);
+        let headers = headers_with_signature(&header);
+
+        assert!(listener.verify(&headers, PAYLOAD).is_ok());
+        assert!(
+            listener
+                .verify_reader(&headers, PAYLOAD.as_bytes())
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn parses_checkout_session_completed_fields() {
+        let payload = r#"{
+            "id": "evt_1",
+            "type": "checkout.session.completed",
+            "data": {
+                "object": {
+                    "id": "cs_123",
+                    "customer": "cus_123",
+                    "subscription": "sub_123",
+                    "payment_status": "paid",
+                    "amount_total": 4200,
+                    "currency": "usd",
+                    "metadata": { "order_id": "order_123" }
+                }
+            }
+        }"#;
+        let listener = StripeListener::new(SECRET.to_string()).with_clock(|| TIMESTAMP as u64);
+        let header = StripeListener::sign(SECRET, payload, TIMESTAMP);
+
+        let event = listener
+            .process(&headers_with_signature(&header), payload)
+            .unwrap();
+        let raw_via_accessor = event.raw().clone();
+
+        let StripeEvent::CheckoutSessionCompleted(checkout_session, raw) = event else {
+            panic!("expected CheckoutSessionCompleted");
+        };
+        assert_eq!(raw_via_accessor, raw);
+        assert_eq!(checkout_session.id, "cs_123");
+        assert_eq!(checkout_session.customer.as_deref(), Some("cus_123"));
+        assert_eq!(checkout_session.subscription.as_deref(), Some("sub_123"));
+        assert_eq!(checkout_session.payment_status, "paid");
+        assert_eq!(checkout_session.amount_total, Some(4200));
+        assert_eq!(checkout_session.currency.as_deref(), Some("usd"));
+        assert_eq!(
+            checkout_session.metadata.get("order_id").map(String::as_str),
+            Some("order_123")
+        );
+        assert_eq!(raw["id"], "cs_123");
+    }
+
+    #[test]
+    fn checkout_session_metadata_defaults_when_absent() {
+        let payload = r#"{
+            "id": "evt_1",
+            "type": "checkout.session.completed",
+            "data": {
+                "object": { "id": "cs_123", "payment_status": "paid" }
+            }
+        }"#;
+        let listener = StripeListener::new(SECRET.to_string()).with_clock(|| TIMESTAMP as u64);
+        let header = StripeListener::sign(SECRET, payload, TIMESTAMP);
+
+        let event = listener
+            .process(&headers_with_signature(&header), payload)
+            .unwrap();
+
+        let StripeEvent::CheckoutSessionCompleted(checkout_session, _) = event else {
+            panic!("expected CheckoutSessionCompleted");
+        };
+        assert!(checkout_session.metadata.is_empty());
+    }
+
+    #[test]
+    fn parses_customer_subscription_deleted_fields() {
+        let payload = r#"{
+            "id": "evt_1",
+            "type": "customer.subscription.deleted",
+            "data": {
+                "object": {
+                    "id": "sub_123",
+                    "customer": "cus_123",
+                    "status": "canceled",
+                    "current_period_end": 1700000000,
+                    "cancel_at_period_end": false,
+                    "items": { "data": [] }
+                }
+            }
+        }"#;
+        let listener = StripeListener::new(SECRET.to_string()).with_clock(|| TIMESTAMP as u64);
+        let header = StripeListener::sign(SECRET, payload, TIMESTAMP);
+
+        let event = listener
+            .process(&headers_with_signature(&header), payload)
+            .unwrap();
+
+        let StripeEvent::CustomerSubscriptionDeleted(subscription, raw) = event else {
+            panic!("expected CustomerSubscriptionDeleted");
+        };
+        assert_eq!(subscription.id, "sub_123");
+        assert_eq!(subscription.customer, "cus_123");
+        assert_eq!(subscription.status, "canceled");
+        assert_eq!(subscription.current_period_end, 1700000000);
+        assert!(!subscription.cancel_at_period_end);
+        assert_eq!(raw["id"], "sub_123");
+    }
+}